@@ -0,0 +1,48 @@
+//! Serde mirrors of the ACME JSON objects (RFC 8555).
+#![allow(non_snake_case)]
+use serde::{Deserialize, Serialize};
+
+/// The `newAccount` request/response object.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct ApiAccount {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub contact: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub termsOfServiceAgreed: Option<bool>,
+    /// External Account Binding, a flattened HMAC-signed JWS embedded in the
+    /// `newAccount` request for CAs that require it (ZeroSSL, Google Trust
+    /// Services, SSL.com). Absent for CAs that don't.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub externalAccountBinding: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub orders: Option<String>,
+}
+
+/// The ACME directory object listing the API endpoints.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct ApiDirectory {
+    pub newNonce: String,
+    pub newAccount: String,
+    pub newOrder: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub newAuthz: Option<String>,
+    pub revokeCert: String,
+    pub keyChange: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub meta: Option<ApiDirectoryMeta>,
+}
+
+/// Optional metadata carried by the directory object.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct ApiDirectoryMeta {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub termsOfService: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub website: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub caaIdentities: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub externalAccountRequired: Option<bool>,
+}
@@ -1,10 +1,11 @@
 //
 use crate::acc::AcmeKey;
 use crate::api::{ApiAccount, ApiDirectory};
-use crate::jwt::make_jws_jwk;
+use crate::jwt::{make_jws, make_jws_jwk};
 use crate::persist::{Persist, PersistKey, PersistKind};
-use crate::util::{expect_header, read_json, retry_call};
+use crate::util::{expect_header, make_eab, make_key_change, read_json, retry_call, KeyType, RetryPolicy};
 use crate::{Account, Result};
+use serde_json::json;
 
 const LETSENCRYPT: &str = "https://acme-v02.api.letsencrypt.org/directory";
 const LETSENCRYPT_STAGING: &str = "https://acme-staging-v02.api.letsencrypt.org/directory";
@@ -34,15 +35,22 @@ impl<'a> DirectoryUrl<'a> {
 
 /// Entry point for accessing an ACME API.
 #[derive(Debug, Clone)]
-pub struct Directory<P: Persist>(P, ApiDirectory);
+pub struct Directory<P: Persist>(P, ApiDirectory, RetryPolicy);
 
 impl<P: Persist> Directory<P> {
     /// Create a directory over a persistence implementation and directory url.
     pub fn from_url(persist: P, url: DirectoryUrl) -> Result<Directory<P>> {
         let dir_url = url.to_url();
-        let res = retry_call(|| Ok((ureq::get(dir_url), None)))?;
+        let policy = RetryPolicy::default();
+        let res = retry_call(&policy, || Ok((ureq::get(dir_url), None)))?;
         let api_dir: ApiDirectory = read_json(res)?;
-        Ok(Directory(persist, api_dir))
+        Ok(Directory(persist, api_dir, policy))
+    }
+
+    /// Replace the retry policy used for all calls made through this directory.
+    pub fn with_retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.2 = policy;
+        self
     }
 
     /// Access an account identified by a contact email.
@@ -56,6 +64,48 @@ impl<P: Persist> Directory<P> {
     /// Either way the `newAccount` API endpoint is called and thereby ensures the
     /// account is active and working.
     pub fn account(&self, contact_email: &str) -> Result<Account<P>> {
+        self.account_impl(contact_email, KeyType::EcP256, None)
+    }
+
+    /// Access an account the same way as [`account`], but supplying an External
+    /// Account Binding (EAB).
+    ///
+    /// Some CAs (ZeroSSL, Google Trust Services, SSL.com) require the
+    /// `newAccount` request to be bound to an account at the CA identified by a
+    /// `kid` and a shared HMAC key. `hmac_key_b64` is the base64url encoded MAC
+    /// key as handed out by the CA.
+    ///
+    /// [`account`]: Directory::account
+    pub fn account_with_eab(
+        &self,
+        contact_email: &str,
+        kid: &str,
+        hmac_key_b64: &str,
+    ) -> Result<Account<P>> {
+        self.account_impl(contact_email, KeyType::EcP256, Some((kid, hmac_key_b64)))
+    }
+
+    /// Access an account the same way as [`account`], but choosing the account
+    /// key algorithm for a newly created key.
+    ///
+    /// The key type only applies when a new key is generated; a persisted key
+    /// keeps whatever algorithm it was created with (detected from the PEM).
+    ///
+    /// [`account`]: Directory::account
+    pub fn account_with_key_type(
+        &self,
+        contact_email: &str,
+        key_type: KeyType,
+    ) -> Result<Account<P>> {
+        self.account_impl(contact_email, key_type, None)
+    }
+
+    fn account_impl(
+        &self,
+        contact_email: &str,
+        key_type: KeyType,
+        eab: Option<(&str, &str)>,
+    ) -> Result<Account<P>> {
         // key in persistence for acme account private key
         let pem_key = PersistKey::new(&contact_email, PersistKind::PrivateKey, "acme_account");
 
@@ -70,18 +120,28 @@ impl<P: Persist> Directory<P> {
             // create a new key (and new account)
             debug!("Create new acme account key");
             is_new = true;
-            AcmeKey::new()
+            AcmeKey::new_with_type(key_type)
         };
 
         // Prepare making a call to newAccount. This is fine to do both for
         // new keys and existing. For existing the spec says to return a 200
         // with the Location header set to the key id (kid).
+        // If the CA requires an external account binding, build the flattened
+        // HMAC-signed JWS over the account key's public JWK.
+        let external_account_binding = match eab {
+            Some((kid, hmac_key_b64)) => {
+                Some(make_eab(&self.1.newAccount, &acme_key, kid, hmac_key_b64)?)
+            }
+            None => None,
+        };
+
         let acc = ApiAccount {
             contact: vec![format!("mailto:{}", contact_email)],
             termsOfServiceAgreed: Some(true),
+            externalAccountBinding: external_account_binding,
             ..Default::default()
         };
-        let res = retry_call(|| {
+        let res = retry_call(&self.2, || {
             let nonce = self.new_nonce()?;
             let url = &self.1.newAccount;
             let body = make_jws_jwk(url, nonce, &acme_key, &acc)?;
@@ -114,7 +174,7 @@ impl<P: Persist> Directory<P> {
 
     pub(crate) fn new_nonce(&self) -> Result<String> {
         debug!("Get new nonce");
-        let res = retry_call(|| Ok((ureq::head(&self.1.newNonce), None)))?;
+        let res = retry_call(&self.2, || Ok((ureq::head(&self.1.newNonce), None)))?;
         expect_header(&res, "replay-nonce")
     }
 
@@ -126,6 +186,101 @@ impl<P: Persist> Directory<P> {
     pub(crate) fn persist(&self) -> &P {
         &self.0
     }
+
+    pub(crate) fn retry_policy(&self) -> &RetryPolicy {
+        &self.2
+    }
+}
+
+impl<P: Persist> Account<P> {
+    /// Update the contact addresses registered with the ACME account.
+    ///
+    /// POSTs an `ApiAccount` carrying the new `contact` array to the account's
+    /// own kid URL, signed with the account key.
+    ///
+    /// Bare email addresses are prefixed with `mailto:` to match the create
+    /// path ([`Directory::account`]); values that already carry a URI scheme
+    /// (e.g. `mailto:`/`tel:`) are passed through unchanged.
+    pub fn update_contacts(&self, contacts: &[&str]) -> Result<()> {
+        let acc = ApiAccount {
+            contact: contacts
+                .iter()
+                .map(|c| {
+                    if c.contains(':') {
+                        c.to_string()
+                    } else {
+                        format!("mailto:{}", c)
+                    }
+                })
+                .collect(),
+            ..Default::default()
+        };
+        let dir = self.directory();
+        let url = self.acme_key().key_id().to_string();
+        retry_call(dir.retry_policy(), || {
+            let nonce = dir.new_nonce()?;
+            let body = make_jws(&url, nonce, self.acme_key(), &acc)?;
+            debug!("Update account contacts: {}", url);
+            let mut req = ureq::post(&url);
+            req.set("content-type", "application/jose+json");
+            Ok((req, Some(body)))
+        })?;
+        Ok(())
+    }
+
+    /// Roll over the account key (RFC 8555 `keyChange`).
+    ///
+    /// Generates a fresh [`AcmeKey`] of the same algorithm as the current one
+    /// and posts a nested JWS to the directory's `keyChange` endpoint: the inner
+    /// JWS is signed by the new key embedding its `jwk`, the outer JWS by the
+    /// current key using its `kid`. On success the new key inherits the old
+    /// `kid`, is persisted over the `acme_account` entry and swapped into this
+    /// live account, so the old key can be discarded.
+    pub fn change_key(&mut self) -> Result<()> {
+        let dir = self.directory().clone();
+        let key_change_url = dir.api_directory().keyChange.clone();
+        let old_kid = self.acme_key().key_id().to_string();
+        let mut new_key = AcmeKey::new_with_type(self.acme_key().key_type());
+        retry_call(dir.retry_policy(), || {
+            let nonce = dir.new_nonce()?;
+            let body = make_key_change(&key_change_url, &old_kid, self.acme_key(), &new_key, nonce)?;
+            debug!("Roll over account key: {}", key_change_url);
+            let mut req = ureq::post(&key_change_url);
+            req.set("content-type", "application/jose+json");
+            Ok((req, Some(body.to_string())))
+        })?;
+        // The new key keeps the account identity (kid).
+        new_key.set_key_id(old_kid);
+        // Persist the new key over the old one and swap it into the account.
+        let pem_key =
+            PersistKey::new(self.contact_email(), PersistKind::PrivateKey, "acme_account");
+        dir.persist().put(&pem_key, &new_key.to_pem())?;
+        *self.acme_key_mut() = new_key;
+        Ok(())
+    }
+
+    /// Deactivate the ACME account.
+    ///
+    /// POSTs `{"status": "deactivated"}` to the account's own kid URL and, on
+    /// success, removes the persisted account key. A deactivated account can no
+    /// longer be used to issue certificates.
+    pub fn deactivate(&self) -> Result<()> {
+        let dir = self.directory();
+        let url = self.acme_key().key_id().to_string();
+        let payload = json!({ "status": "deactivated" });
+        retry_call(dir.retry_policy(), || {
+            let nonce = dir.new_nonce()?;
+            let body = make_jws(&url, nonce, self.acme_key(), &payload)?;
+            debug!("Deactivate account: {}", url);
+            let mut req = ureq::post(&url);
+            req.set("content-type", "application/jose+json");
+            Ok((req, Some(body)))
+        })?;
+        let pem_key =
+            PersistKey::new(self.contact_email(), PersistKind::PrivateKey, "acme_account");
+        dir.persist().remove(&pem_key)?;
+        Ok(())
+    }
 }
 
 #[cfg(test)]
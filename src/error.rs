@@ -0,0 +1,55 @@
+use crate::util::ApiProblem;
+use std::fmt;
+
+/// Result type used throughout the crate.
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Errors produced by this crate.
+#[derive(Debug)]
+pub enum Error {
+    /// A call to the ACME API failed with a message.
+    Call(String),
+    /// A structured ACME `application/problem+json` error returned by the CA.
+    /// Match on [`ApiProblem::kind`] to distinguish retryable from permanent
+    /// conditions.
+    ApiProblem(ApiProblem),
+    /// Failed to base64url-decode a value.
+    Base64Decode(base64::DecodeError),
+    /// A JSON (de)serialization error.
+    Json(serde_json::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Call(s) => write!(f, "{}", s),
+            Error::ApiProblem(p) => match (&p._type, &p.detail) {
+                (Some(t), Some(d)) => write!(f, "{}: {}", t, d),
+                (Some(t), None) => write!(f, "{}", t),
+                _ => write!(f, "ACME problem"),
+            },
+            Error::Base64Decode(e) => write!(f, "base64 decode: {}", e),
+            Error::Json(e) => write!(f, "json: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<String> for Error {
+    fn from(s: String) -> Self {
+        Error::Call(s)
+    }
+}
+
+impl From<&str> for Error {
+    fn from(s: &str) -> Self {
+        Error::Call(s.to_string())
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self {
+        Error::Json(e)
+    }
+}
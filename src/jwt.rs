@@ -0,0 +1,61 @@
+//! JWS construction for ACME requests (RFC 8555 §6.2).
+//!
+//! Both the `jwk`- and `kid`-signed flavours delegate the algorithm-specific
+//! work to [`AcmeKey`], so an account created with a P-384 or RSA key signs and
+//! serializes its JWK correctly without any branching here.
+use crate::util::{base64url, AcmeKey};
+use crate::Result;
+use serde::Serialize;
+use serde_json::json;
+
+/// Build a `jwk`-signed JWS, embedding the account key's public JWK in the
+/// protected header. Used for `newAccount`, where the CA does not yet know the
+/// account key.
+pub(crate) fn make_jws_jwk<T: Serialize>(
+    url: &str,
+    nonce: String,
+    key: &AcmeKey,
+    payload: &T,
+) -> Result<String> {
+    let protected = json!({
+        "alg": key.alg(),
+        "nonce": nonce,
+        "url": url,
+        "jwk": key.public_jwk()?,
+    });
+    sign(protected, payload, key)
+}
+
+/// Build a `kid`-signed JWS, referencing the account by its key id. Used for
+/// every request made after the account has been created.
+pub(crate) fn make_jws<T: Serialize>(
+    url: &str,
+    nonce: String,
+    key: &AcmeKey,
+    payload: &T,
+) -> Result<String> {
+    let protected = json!({
+        "alg": key.alg(),
+        "nonce": nonce,
+        "url": url,
+        "kid": key.key_id(),
+    });
+    sign(protected, payload, key)
+}
+
+/// Serialize the protected header and payload, sign the `protected.payload`
+/// input with the account key and assemble the flattened JWS.
+fn sign<T: Serialize>(
+    protected: serde_json::Value,
+    payload: &T,
+    key: &AcmeKey,
+) -> Result<String> {
+    let protected = base64url(&serde_json::to_vec(&protected)?);
+    let payload = base64url(&serde_json::to_vec(payload)?);
+    let signature = key.sign(format!("{}.{}", protected, payload).as_bytes())?;
+    Ok(serde_json::to_string(&json!({
+        "protected": protected,
+        "payload": payload,
+        "signature": base64url(&signature),
+    }))?)
+}
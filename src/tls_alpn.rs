@@ -0,0 +1,51 @@
+//! TLS-ALPN-01 challenge (RFC 8737).
+//!
+//! Unlike the http-01 and dns-01 challenges this needs no HTTP server or DNS
+//! record: validation happens over a single TLS handshake on port 443, which
+//! makes it usable behind load balancers where only 443 is reachable.
+use crate::util::{create_tls_alpn_01_cert, ACME_TLS_ALPN_NAME};
+use crate::Result;
+
+/// A prepared answer to a TLS-ALPN-01 challenge.
+///
+/// Install [`private_key`] and [`certificate`] on a TLS listener for the
+/// challenged identifier, selecting this certificate only when the client
+/// negotiates the [`alpn_protocol`] ALPN protocol. The CA then opens such a
+/// handshake and reads the key-authorization digest from the certificate.
+///
+/// [`private_key`]: TlsAlpn01Challenge::private_key
+/// [`certificate`]: TlsAlpn01Challenge::certificate
+/// [`alpn_protocol`]: TlsAlpn01Challenge::alpn_protocol
+pub struct TlsAlpn01Challenge {
+    private_key_pem: String,
+    certificate_pem: String,
+}
+
+impl TlsAlpn01Challenge {
+    /// Build the self-signed validation certificate for `domain` answering the
+    /// challenge whose key authorization is `token + "." + base64url(thumbprint)`.
+    pub fn new(domain: &str, key_authorization: &str) -> Result<TlsAlpn01Challenge> {
+        let (private_key_pem, certificate_pem) =
+            create_tls_alpn_01_cert(domain, key_authorization)?;
+        Ok(TlsAlpn01Challenge {
+            private_key_pem,
+            certificate_pem,
+        })
+    }
+
+    /// The PEM-encoded private key to install on the validation listener.
+    pub fn private_key(&self) -> &str {
+        &self.private_key_pem
+    }
+
+    /// The PEM-encoded self-signed validation certificate.
+    pub fn certificate(&self) -> &str {
+        &self.certificate_pem
+    }
+
+    /// The ALPN protocol the listener must select for this certificate
+    /// (`"acme-tls/1"`).
+    pub fn alpn_protocol(&self) -> &'static str {
+        ACME_TLS_ALPN_NAME
+    }
+}
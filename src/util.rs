@@ -1,15 +1,30 @@
-use crate::cert::EC_GROUP_P256;
 use lazy_static::lazy_static;
+use openssl::bn::BigNumContext;
 use openssl::ec::EcKey;
-use openssl::pkey;
+use openssl::hash::MessageDigest;
+use openssl::pkey::{self, PKey};
+use openssl::sign::Signer;
 use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::json;
 use std::io::Read;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use crate::{Error, Result};
 
 lazy_static! {
     static ref BASE64_CONFIG: base64::Config =
         { base64::Config::new(base64::CharacterSet::UrlSafe, false) };
+
+    /// The P-256 curve group, used for ES256 account keys (the default).
+    pub(crate) static ref EC_GROUP_P256: openssl::ec::EcGroup =
+        openssl::ec::EcGroup::from_curve_name(openssl::nid::Nid::X9_62_PRIME256V1)
+            .expect("P-256 group");
+    /// The P-384 curve group, used for ES384 account keys.
+    pub(crate) static ref EC_GROUP_P384: openssl::ec::EcGroup =
+        openssl::ec::EcGroup::from_curve_name(openssl::nid::Nid::SECP384R1)
+            .expect("P-384 group");
 }
 
 pub(crate) fn base64url<T: ?Sized + AsRef<[u8]>>(input: &T) -> String {
@@ -42,13 +57,109 @@ pub(crate) fn configure_req(req: &mut ureq::Request) {
     req.timeout_write(30_000);
 }
 
+/// Policy governing how [`retry_call`] backs off and retries failed calls.
+///
+/// ACME servers signal retryable conditions through `application/problem+json`
+/// bodies and `Retry-After` headers; this policy decides how many times and how
+/// long to wait before giving up.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Maximum number of attempts (including the first).
+    pub max_attempts: usize,
+    /// Delay before the first retry.
+    pub base_delay: Duration,
+    /// Upper bound on any single backoff delay.
+    pub max_delay: Duration,
+    /// Whether to add random jitter to the computed backoff.
+    pub jitter: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            base_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(30),
+            jitter: true,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Exponential backoff for the n:th attempt (1-based), capped at `max_delay`
+    /// and optionally jittered.
+    fn backoff(&self, attempt: usize) -> Duration {
+        let factor = 1u32 << (attempt.saturating_sub(1).min(16) as u32);
+        let mut delay = self.base_delay * factor;
+        if delay > self.max_delay {
+            delay = self.max_delay;
+        }
+        if self.jitter && !delay.is_zero() {
+            // Derive a cheap pseudo-random fraction from the current time so we
+            // don't need to pull in a rng dependency.
+            let nanos = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.subsec_nanos())
+                .unwrap_or(0);
+            let frac = (nanos % 1000) as f64 / 1000.0;
+            delay = delay.mul_f64(0.5 + 0.5 * frac);
+        }
+        delay
+    }
+}
+
+/// A parsed ACME `application/problem+json` document (RFC 7807 / RFC 8555).
+#[derive(Debug, Clone, Deserialize)]
+pub struct ApiProblem {
+    #[serde(rename = "type")]
+    pub _type: Option<String>,
+    pub detail: Option<String>,
+    pub status: Option<u16>,
+}
+
+/// The ACME error kind carried by the `type` field of an [`ApiProblem`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AcmeErrorKind {
+    BadNonce,
+    RateLimited,
+    Other(String),
+}
+
+impl ApiProblem {
+    /// The ACME error kind, parsed from the `urn:ietf:params:acme:error:*`
+    /// `type` field.
+    pub fn kind(&self) -> AcmeErrorKind {
+        const PREFIX: &str = "urn:ietf:params:acme:error:";
+        match self._type.as_deref() {
+            Some(t) if t == format!("{}badNonce", PREFIX) => AcmeErrorKind::BadNonce,
+            Some(t) if t == format!("{}rateLimited", PREFIX) => AcmeErrorKind::RateLimited,
+            Some(t) => AcmeErrorKind::Other(t.to_string()),
+            None => AcmeErrorKind::Other(String::new()),
+        }
+    }
+
+    /// Whether the condition is transient and worth retrying. A `badNonce` is
+    /// always retryable since the next attempt fetches a fresh nonce.
+    fn is_retryable(&self) -> bool {
+        matches!(self.kind(), AcmeErrorKind::BadNonce | AcmeErrorKind::RateLimited)
+    }
+}
+
+/// Call `f` to build a request, send it and retry transient failures according
+/// to `policy`.
+///
+/// The closure is re-invoked for every attempt, so a `badNonce` response is
+/// transparently recovered from: the next build fetches a fresh nonce. A
+/// `Retry-After` header (seconds or an HTTP-date) takes precedence over the
+/// computed exponential backoff.
 pub(crate) fn retry_call<F: Fn() -> Result<(ureq::Request, Option<String>)>>(
+    policy: &RetryPolicy,
     f: F,
 ) -> Result<ureq::Response> {
-    let mut i = 0;
+    let mut attempt = 0;
     loop {
+        attempt += 1;
         let (mut req, body) = f()?;
-        i += 1;
         configure_req(&mut req);
         let res = if let Some(body) = body {
             trace!("{:?}: {}", req, body);
@@ -62,16 +173,86 @@ pub(crate) fn retry_call<F: Fn() -> Result<(ureq::Request, Option<String>)>>(
             return Ok(res);
         }
         trace!("{:?}", res);
-        if i == 3 || res.status() == 400 {
+        let status = res.status();
+        let retry_after = res.header("retry-after").and_then(parse_retry_after);
+        let res_body = safe_read_string(res)?;
+        let problem: Option<ApiProblem> = serde_json::from_str(&res_body).ok();
+
+        // A structured problem decides retryability; otherwise only 5xx retries.
+        let retryable = problem
+            .as_ref()
+            .map(ApiProblem::is_retryable)
+            .unwrap_or(status >= 500);
+
+        if attempt >= policy.max_attempts || !retryable {
             trace!("No more retries");
-            let status = res.status();
-            let res_body = safe_read_string(res)?;
-            return Err(Error::Call(format!(
-                "Call failed ({}): {}",
-                status, res_body
-            )));
+            return Err(match problem {
+                Some(problem) => Error::ApiProblem(problem),
+                None => Error::Call(format!("Call failed ({}): {}", status, res_body)),
+            });
         }
-        trace!("Retry call");
+
+        let delay = retry_after.unwrap_or_else(|| policy.backoff(attempt));
+        trace!("Retry call after {:?}", delay);
+        thread::sleep(delay);
+    }
+}
+
+/// Parse a `Retry-After` header value, which is either a number of seconds or
+/// an HTTP-date, into a delay from now.
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let target = parse_http_date(value)?;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .ok()?
+        .as_secs();
+    Some(Duration::from_secs(target.saturating_sub(now)))
+}
+
+/// Parse an RFC 1123 HTTP-date (e.g. `Wed, 21 Oct 2015 07:28:00 GMT`) into a
+/// unix timestamp. Returns `None` for any value that doesn't parse.
+fn parse_http_date(value: &str) -> Option<u64> {
+    // "Wed, 21 Oct 2015 07:28:00 GMT"
+    let rest = value.split_once(", ").map(|(_, r)| r).unwrap_or(value);
+    let mut parts = rest.split_whitespace();
+    let day: i64 = parts.next()?.parse().ok()?;
+    let month = match parts.next()? {
+        "Jan" => 1,
+        "Feb" => 2,
+        "Mar" => 3,
+        "Apr" => 4,
+        "May" => 5,
+        "Jun" => 6,
+        "Jul" => 7,
+        "Aug" => 8,
+        "Sep" => 9,
+        "Oct" => 10,
+        "Nov" => 11,
+        "Dec" => 12,
+        _ => return None,
+    };
+    let year: i64 = parts.next()?.parse().ok()?;
+    let mut hms = parts.next()?.splitn(3, ':');
+    let h: i64 = hms.next()?.parse().ok()?;
+    let m: i64 = hms.next()?.parse().ok()?;
+    let s: i64 = hms.next()?.parse().ok()?;
+
+    // days-from-civil (Howard Hinnant's algorithm)
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = if y >= 0 { y } else { y - 399 } / 400;
+    let yoe = y - era * 400;
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146097 + doe - 719468;
+    let secs = days * 86400 + h * 3600 + m * 60 + s;
+    if secs < 0 {
+        None
+    } else {
+        Some(secs as u64)
     }
 }
 
@@ -81,38 +262,377 @@ pub(crate) fn expect_header(res: &ureq::Response, name: &str) -> Result<String>
         .ok_or_else(|| format!("Missing header: {}", name).into())
 }
 
+/// The algorithm an [`AcmeKey`] signs with. Selects the JWS `alg` and the JWK
+/// serialization.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyType {
+    /// ECDSA over P-256, signing with ES256. The default.
+    EcP256,
+    /// ECDSA over P-384, signing with ES384.
+    EcP384,
+    /// RSA (2048 bit), signing with RS256.
+    Rsa,
+}
+
+impl KeyType {
+    /// The JWS `alg` header value for this key type.
+    pub(crate) fn alg(self) -> &'static str {
+        match self {
+            KeyType::EcP256 => "ES256",
+            KeyType::EcP384 => "ES384",
+            KeyType::Rsa => "RS256",
+        }
+    }
+
+    /// The elliptic curve group, or `None` for RSA.
+    fn ec_group(self) -> Option<&'static openssl::ec::EcGroupRef> {
+        match self {
+            KeyType::EcP256 => Some(&EC_GROUP_P256),
+            KeyType::EcP384 => Some(&EC_GROUP_P384),
+            KeyType::Rsa => None,
+        }
+    }
+
+    /// The message digest used when signing with this key type.
+    fn digest(self) -> MessageDigest {
+        match self {
+            KeyType::EcP256 => MessageDigest::sha256(),
+            KeyType::EcP384 => MessageDigest::sha384(),
+            KeyType::Rsa => MessageDigest::sha256(),
+        }
+    }
+
+    /// Fixed size (bytes) of each EC coordinate / signature half.
+    fn ec_field_size(self) -> usize {
+        match self {
+            KeyType::EcP256 => 32,
+            KeyType::EcP384 => 48,
+            KeyType::Rsa => 0,
+        }
+    }
+}
+
 #[derive(Clone)]
-pub(crate) struct AcmeKey(EcKey<pkey::Private>, Option<String>);
+enum KeyInner {
+    Ec(EcKey<pkey::Private>),
+    Rsa(openssl::rsa::Rsa<pkey::Private>),
+}
+
+#[derive(Clone)]
+pub(crate) struct AcmeKey {
+    inner: KeyInner,
+    key_type: KeyType,
+    key_id: Option<String>,
+}
 
 impl AcmeKey {
     pub(crate) fn new() -> AcmeKey {
-        let pri_key = EcKey::generate(&*EC_GROUP_P256).expect("EcKey");
-        Self::from_key(pri_key)
+        Self::new_with_type(KeyType::EcP256)
+    }
+
+    pub(crate) fn new_with_type(key_type: KeyType) -> AcmeKey {
+        let inner = match key_type {
+            KeyType::EcP256 | KeyType::EcP384 => {
+                let group = key_type.ec_group().expect("ec group");
+                KeyInner::Ec(EcKey::generate(group).expect("EcKey"))
+            }
+            KeyType::Rsa => {
+                KeyInner::Rsa(openssl::rsa::Rsa::generate(2048).expect("Rsa"))
+            }
+        };
+        AcmeKey {
+            inner,
+            key_type,
+            key_id: None,
+        }
     }
 
     pub(crate) fn from_pem(pem: &[u8]) -> Result<AcmeKey> {
-        let pri_key =
-            EcKey::private_key_from_pem(pem).map_err(|e| format!("Failed to read PEM: {}", e))?;
-        Ok(Self::from_key(pri_key))
+        // Detect the algorithm from the PEM: EC keys parse as EcKey (the curve
+        // then distinguishes P-256 from P-384); anything else is tried as RSA.
+        if let Ok(ec) = EcKey::private_key_from_pem(pem) {
+            let key_type = match ec.group().curve_name() {
+                Some(openssl::nid::Nid::X9_62_PRIME256V1) => KeyType::EcP256,
+                Some(openssl::nid::Nid::SECP384R1) => KeyType::EcP384,
+                other => {
+                    return Err(format!("Unsupported EC curve: {:?}", other).into());
+                }
+            };
+            return Ok(AcmeKey {
+                inner: KeyInner::Ec(ec),
+                key_type,
+                key_id: None,
+            });
+        }
+        let rsa = openssl::rsa::Rsa::private_key_from_pem(pem)
+            .map_err(|e| format!("Failed to read PEM: {}", e))?;
+        Ok(AcmeKey {
+            inner: KeyInner::Rsa(rsa),
+            key_type: KeyType::Rsa,
+            key_id: None,
+        })
     }
 
-    fn from_key(pri_key: EcKey<pkey::Private>) -> AcmeKey {
-        AcmeKey(pri_key, None)
+    pub(crate) fn key_type(&self) -> KeyType {
+        self.key_type
     }
 
-    pub(crate) fn to_pem(&self) -> Vec<u8> {
-        self.0.private_key_to_pem().expect("private_key_to_pem")
+    /// The JWS `alg` header value for this key.
+    pub(crate) fn alg(&self) -> &'static str {
+        self.key_type.alg()
     }
 
-    pub(crate) fn private_key(&self) -> &EcKey<pkey::Private> {
-        &self.0
+    pub(crate) fn to_pem(&self) -> Vec<u8> {
+        match &self.inner {
+            KeyInner::Ec(ec) => ec.private_key_to_pem().expect("private_key_to_pem"),
+            KeyInner::Rsa(rsa) => rsa.private_key_to_pem().expect("private_key_to_pem"),
+        }
     }
 
     pub(crate) fn key_id(&self) -> &str {
-        self.1.as_ref().unwrap()
+        self.key_id.as_ref().unwrap()
     }
 
     pub(crate) fn set_key_id(&mut self, kid: String) {
-        self.1 = Some(kid)
+        self.key_id = Some(kid)
+    }
+
+    /// Sign `data`, producing a JWS signature appropriate for this key's `alg`:
+    /// raw `r || s` for ECDSA, PKCS#1 v1.5 for RSA.
+    pub(crate) fn sign(&self, data: &[u8]) -> Result<Vec<u8>> {
+        match &self.inner {
+            KeyInner::Ec(ec) => ecdsa_sign(ec, data, self.key_type),
+            KeyInner::Rsa(rsa) => {
+                let pkey = PKey::from_rsa(rsa.clone()).map_err(|e| format!("pkey: {}", e))?;
+                let mut signer = Signer::new(self.key_type.digest(), &pkey)
+                    .map_err(|e| format!("signer: {}", e))?;
+                signer.update(data).map_err(|e| format!("update: {}", e))?;
+                signer.sign_to_vec().map_err(|e| format!("sign: {}", e).into())
+            }
+        }
+    }
+
+    /// The public JWK for this account key, as embedded in the protected
+    /// header of a `jwk`-signed JWS. Same shape as the one built by
+    /// `make_jws_jwk`, factored out here so it can also be used as the
+    /// payload of an external account binding.
+    pub(crate) fn public_jwk(&self) -> Result<serde_json::Value> {
+        match &self.inner {
+            KeyInner::Ec(ec) => {
+                let group = self.key_type.ec_group().expect("ec group");
+                let mut ctx = BigNumContext::new().map_err(|e| format!("BigNumContext: {}", e))?;
+                let mut x = openssl::bn::BigNum::new().map_err(|e| format!("BigNum: {}", e))?;
+                let mut y = openssl::bn::BigNum::new().map_err(|e| format!("BigNum: {}", e))?;
+                ec.public_key()
+                    .affine_coordinates_gfp(group, &mut x, &mut y, &mut ctx)
+                    .map_err(|e| format!("affine_coordinates_gfp: {}", e))?;
+                let size = self.key_type.ec_field_size();
+                let crv = match self.key_type {
+                    KeyType::EcP256 => "P-256",
+                    KeyType::EcP384 => "P-384",
+                    KeyType::Rsa => unreachable!(),
+                };
+                Ok(json!({
+                    "crv": crv,
+                    "kty": "EC",
+                    "x": base64url(&pad_left(&x.to_vec(), size)),
+                    "y": base64url(&pad_left(&y.to_vec(), size)),
+                }))
+            }
+            KeyInner::Rsa(rsa) => Ok(json!({
+                "e": base64url(&rsa.e().to_vec()),
+                "kty": "RSA",
+                "n": base64url(&rsa.n().to_vec()),
+            })),
+        }
+    }
+}
+
+fn pad_left(v: &[u8], len: usize) -> Vec<u8> {
+    if v.len() >= len {
+        return v.to_vec();
     }
+    let mut out = vec![0u8; len - v.len()];
+    out.extend_from_slice(v);
+    out
+}
+
+/// Build a flattened JWS external account binding (EAB).
+///
+/// The protected header carries `alg: HS256`, the CA provided `kid` and the
+/// `newAccount` `url` (no nonce); the payload is the base64url JSON of the
+/// account key's public JWK; the signature is HMAC-SHA256 over
+/// `protected + "." + payload` using the base64url-decoded MAC key.
+pub(crate) fn make_eab(
+    url: &str,
+    acme_key: &AcmeKey,
+    kid: &str,
+    hmac_key_b64: &str,
+) -> Result<serde_json::Value> {
+    let protected = json!({
+        "alg": "HS256",
+        "kid": kid,
+        "url": url,
+    });
+    let protected = base64url(&serde_json::to_vec(&protected)?);
+    let payload = base64url(&serde_json::to_vec(&acme_key.public_jwk()?)?);
+    let mac_key = unbase64url(hmac_key_b64)?;
+    let signing_input = format!("{}.{}", protected, payload);
+    let signature = hmac_sha256(&mac_key, signing_input.as_bytes())?;
+    Ok(json!({
+        "protected": protected,
+        "payload": payload,
+        "signature": base64url(&signature),
+    }))
+}
+
+/// The ALPN protocol string a TLS-ALPN-01 validation listener must negotiate.
+pub(crate) const ACME_TLS_ALPN_NAME: &str = "acme-tls/1";
+
+/// Build the self-signed certificate and key answering a TLS-ALPN-01 challenge
+/// for `domain`.
+///
+/// The certificate carries a critical `id-pe-acmeIdentifier` extension (OID
+/// `1.3.6.1.5.5.7.1.31`) whose value is the DER encoding of an `OCTET STRING`
+/// wrapping the 32-byte SHA-256 digest of the key authorization. Returns the
+/// `(private_key_pem, certificate_pem)` the caller installs on a listener that
+/// selects this certificate only for the `acme-tls/1` ALPN handshake.
+pub(crate) fn create_tls_alpn_01_cert(
+    domain: &str,
+    key_authorization: &str,
+) -> Result<(String, String)> {
+    use openssl::asn1::{Asn1Object, Asn1Time};
+    use openssl::bn::BigNum;
+    use openssl::hash::hash;
+    use openssl::x509::extension::SubjectAlternativeName;
+    use openssl::x509::{X509Builder, X509Extension, X509NameBuilder};
+
+    // Fresh key for the ephemeral validation certificate.
+    let ec = EcKey::generate(&*EC_GROUP_P256).map_err(|e| format!("EcKey: {}", e))?;
+    let pkey = PKey::from_ec_key(ec).map_err(|e| format!("PKey: {}", e))?;
+
+    // SHA-256 of the key authorization, DER wrapped as an OCTET STRING.
+    let digest = hash(MessageDigest::sha256(), key_authorization.as_bytes())
+        .map_err(|e| format!("sha256: {}", e))?;
+    let mut acme_ext_der = vec![0x04u8, 0x20]; // OCTET STRING, length 32
+    acme_ext_der.extend_from_slice(&digest);
+
+    let mut name = X509NameBuilder::new().map_err(|e| format!("name: {}", e))?;
+    name.append_entry_by_text("CN", domain)
+        .map_err(|e| format!("CN: {}", e))?;
+    let name = name.build();
+
+    let mut builder = X509Builder::new().map_err(|e| format!("x509: {}", e))?;
+    builder.set_version(2).map_err(|e| format!("version: {}", e))?;
+    let serial = BigNum::from_u32(1)
+        .and_then(|b| b.to_asn1_integer())
+        .map_err(|e| format!("serial: {}", e))?;
+    builder.set_serial_number(&serial).map_err(|e| format!("serial: {}", e))?;
+    builder.set_subject_name(&name).map_err(|e| format!("subject: {}", e))?;
+    builder.set_issuer_name(&name).map_err(|e| format!("issuer: {}", e))?;
+    builder.set_pubkey(&pkey).map_err(|e| format!("pubkey: {}", e))?;
+    let not_before = Asn1Time::days_from_now(0).map_err(|e| format!("time: {}", e))?;
+    let not_after = Asn1Time::days_from_now(7).map_err(|e| format!("time: {}", e))?;
+    builder.set_not_before(&not_before).map_err(|e| format!("time: {}", e))?;
+    builder.set_not_after(&not_after).map_err(|e| format!("time: {}", e))?;
+
+    let san = SubjectAlternativeName::new()
+        .dns(domain)
+        .build(&builder.x509v3_context(None, None))
+        .map_err(|e| format!("san: {}", e))?;
+    builder.append_extension(san).map_err(|e| format!("san: {}", e))?;
+
+    // The critical id-pe-acmeIdentifier extension.
+    let oid = Asn1Object::from_str("1.3.6.1.5.5.7.1.31")
+        .map_err(|e| format!("oid: {}", e))?;
+    let acme_ext = X509Extension::new_from_der(&oid, true, &acme_ext_der)
+        .map_err(|e| format!("acmeIdentifier ext: {}", e))?;
+    builder.append_extension(acme_ext).map_err(|e| format!("ext: {}", e))?;
+
+    builder
+        .sign(&pkey, MessageDigest::sha256())
+        .map_err(|e| format!("sign: {}", e))?;
+    let cert = builder.build();
+
+    let cert_pem = String::from_utf8(cert.to_pem().map_err(|e| format!("cert pem: {}", e))?)
+        .map_err(|e| format!("cert pem utf8: {}", e))?;
+    let key_pem = String::from_utf8(
+        pkey.private_key_to_pem_pkcs8()
+            .map_err(|e| format!("key pem: {}", e))?,
+    )
+    .map_err(|e| format!("key pem utf8: {}", e))?;
+    Ok((key_pem, cert_pem))
+}
+
+/// Build the nested JWS posted to the directory's `keyChange` endpoint for an
+/// RFC 8555 account key rollover.
+///
+/// The *inner* JWS is signed by the new key with an embedded `jwk` (not `kid`)
+/// and no nonce, over `{"account": <old kid>, "oldKey": <old JWK>}`. The
+/// *outer* JWS is signed by the old key using its `kid` and a fresh nonce, over
+/// the inner JWS. Both carry `url` set to the `keyChange` endpoint.
+pub(crate) fn make_key_change(
+    key_change_url: &str,
+    old_kid: &str,
+    old_key: &AcmeKey,
+    new_key: &AcmeKey,
+    nonce: String,
+) -> Result<serde_json::Value> {
+    // Inner JWS: signed by the new key, embedding its jwk, no nonce.
+    let inner_protected = json!({
+        "alg": new_key.alg(),
+        "jwk": new_key.public_jwk()?,
+        "url": key_change_url,
+    });
+    let inner_protected = base64url(&serde_json::to_vec(&inner_protected)?);
+    let inner_payload = base64url(&serde_json::to_vec(&json!({
+        "account": old_kid,
+        "oldKey": old_key.public_jwk()?,
+    }))?);
+    let inner_sig = new_key.sign(format!("{}.{}", inner_protected, inner_payload).as_bytes())?;
+    let inner = json!({
+        "protected": inner_protected,
+        "payload": inner_payload,
+        "signature": base64url(&inner_sig),
+    });
+
+    // Outer JWS: signed by the old key using its kid and a fresh nonce.
+    let outer_protected = json!({
+        "alg": old_key.alg(),
+        "kid": old_kid,
+        "nonce": nonce,
+        "url": key_change_url,
+    });
+    let outer_protected = base64url(&serde_json::to_vec(&outer_protected)?);
+    let outer_payload = base64url(&serde_json::to_vec(&inner)?);
+    let outer_sig = old_key.sign(format!("{}.{}", outer_protected, outer_payload).as_bytes())?;
+    Ok(json!({
+        "protected": outer_protected,
+        "payload": outer_payload,
+        "signature": base64url(&outer_sig),
+    }))
+}
+
+/// ECDSA sign `data`, returning the raw `r || s` JWS signature (2 × field size)
+/// rather than the DER encoding openssl produces. The digest and coordinate
+/// width follow `key_type` (ES256 over P-256, ES384 over P-384).
+fn ecdsa_sign(key: &EcKey<pkey::Private>, data: &[u8], key_type: KeyType) -> Result<Vec<u8>> {
+    use openssl::ecdsa::EcdsaSig;
+    use openssl::hash::hash;
+    let digest = hash(key_type.digest(), data).map_err(|e| format!("digest: {}", e))?;
+    let sig = EcdsaSig::sign(&digest, key).map_err(|e| format!("ecdsa sign: {}", e))?;
+    let size = key_type.ec_field_size();
+    let mut out = pad_left(&sig.r().to_vec(), size);
+    out.extend_from_slice(&pad_left(&sig.s().to_vec(), size));
+    Ok(out)
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Result<Vec<u8>> {
+    let pkey = PKey::hmac(key).map_err(|e| format!("hmac key: {}", e))?;
+    let mut signer =
+        Signer::new(MessageDigest::sha256(), &pkey).map_err(|e| format!("hmac signer: {}", e))?;
+    signer
+        .update(data)
+        .map_err(|e| format!("hmac update: {}", e))?;
+    signer.sign_to_vec().map_err(|e| format!("hmac sign: {}", e).into())
 }